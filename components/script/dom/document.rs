@@ -0,0 +1,182 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use dom::bindings::codegen::Bindings::DocumentBinding;
+use dom::bindings::codegen::InheritTypes::DocumentDerived;
+use dom::bindings::global::GlobalRef;
+use dom::bindings::js::{JS, JSRef, Temporary};
+use dom::bindings::utils::{Reflectable, Reflector, reflect_dom_object};
+use dom::eventtarget::{EventTarget, NodeTargetTypeId};
+use dom::htmlscriptelement::{HTMLScriptElement, HTMLScriptElementHelpers, ModuleState};
+use dom::node::{Node, DocumentNodeTypeId};
+use dom::window::Window;
+
+use encoding::types::EncodingRef;
+use url::Url;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[dom_struct]
+pub struct Document {
+    node: Node,
+
+    /// This document's character encoding, used as the last fallback when
+    /// an external classic script specifies none of its own
+    /// (<https://html.spec.whatwg.org/multipage/scripting.html#script-character-encoding>).
+    encoding: Cell<EncodingRef>,
+
+    /// Set once the parser has finished tokenizing the document. Until
+    /// then `deferred_scripts` is never drained, even if every script
+    /// currently in it happens to be ready, since the parser can still
+    /// append more in document order.
+    ///
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#list-of-scripts-that-will-execute-when-the-document-has-finished-parsing>
+    parsing_finished: Cell<bool>,
+
+    /// `defer`red scripts (and `type=module` scripts, which are always
+    /// deferred), in document order. Drained in order once parsing has
+    /// finished and each entry, in turn, has its source available.
+    deferred_scripts: RefCell<Vec<JS<HTMLScriptElement>>>,
+
+    /// `async` scripts, run the moment each one's fetch completes,
+    /// regardless of document order.
+    ///
+    /// <https://html.spec.whatwg.org/multipage/parsing.html#set-of-scripts-that-will-execute-as-soon-as-possible>
+    asap_scripts: RefCell<Vec<JS<HTMLScriptElement>>>,
+
+    /// Per-URL state for this document's `type=module` script graph,
+    /// shared across every module script on the page so that two scripts
+    /// importing the same URL only fetch it once and so that import
+    /// cycles terminate.
+    ///
+    /// <https://html.spec.whatwg.org/multipage/webappapis.html#module-map>
+    module_map: Arc<Mutex<HashMap<Url, ModuleState>>>,
+}
+
+impl DocumentDerived for EventTarget {
+    fn is_document(&self) -> bool {
+        *self.type_id() == NodeTargetTypeId(DocumentNodeTypeId)
+    }
+}
+
+impl Document {
+    fn new_inherited(window: JSRef<Window>, encoding: EncodingRef) -> Document {
+        Document {
+            node: Node::new_document_node(),
+            encoding: Cell::new(encoding),
+            parsing_finished: Cell::new(false),
+            deferred_scripts: RefCell::new(Vec::new()),
+            asap_scripts: RefCell::new(Vec::new()),
+            module_map: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn new(window: JSRef<Window>, encoding: EncodingRef) -> Temporary<Document> {
+        let document = Document::new_inherited(window, encoding);
+        reflect_dom_object(box document, GlobalRef::Window(window), DocumentBinding::Wrap)
+    }
+}
+
+pub trait DocumentHelpers {
+    /// This document's character encoding, used as the last resort of the
+    /// script character encoding fallback chain.
+    fn encoding(self) -> EncodingRef;
+
+    /// Append a `defer`red (or module) script to the list that will run,
+    /// in document order, once parsing has finished.
+    fn add_deferred_script(self, script: JSRef<HTMLScriptElement>);
+
+    /// Add an `async` script to the set that runs as soon as it is ready,
+    /// in whatever order that happens to be.
+    fn add_asap_script(self, script: JSRef<HTMLScriptElement>);
+
+    /// Remove an `async` script from the asap set once it has run (or
+    /// failed to fetch).
+    fn remove_asap_script(self, script: JSRef<HTMLScriptElement>);
+
+    /// Called whenever a deferred script's source becomes available (its
+    /// fetch or module graph completed). Resumes draining the deferred
+    /// list if parsing has already finished and this script was blocking
+    /// the front of the queue.
+    fn mark_deferred_script_ready(self, script: JSRef<HTMLScriptElement>);
+
+    /// This document's module map, shared by every `type=module` script on
+    /// the page.
+    fn module_map(self) -> Arc<Mutex<HashMap<Url, ModuleState>>>;
+
+    /// Called by the HTML parser once tokenizing has finished
+    /// (<https://html.spec.whatwg.org/multipage/parsing.html#the-end>, "the
+    /// list of scripts that will execute when the document has finished
+    /// parsing" step). Drains every deferred script that is already ready,
+    /// in order; any not yet ready are picked up later by
+    /// `mark_deferred_script_ready` as their fetches complete.
+    fn finish_parsing(self);
+}
+
+impl<'a> DocumentHelpers for JSRef<'a, Document> {
+    fn encoding(self) -> EncodingRef {
+        self.deref().encoding.get()
+    }
+
+    fn add_deferred_script(self, script: JSRef<HTMLScriptElement>) {
+        self.deref().deferred_scripts.borrow_mut().push(JS::from_rooted(script));
+    }
+
+    fn add_asap_script(self, script: JSRef<HTMLScriptElement>) {
+        self.deref().asap_scripts.borrow_mut().push(JS::from_rooted(script));
+    }
+
+    fn remove_asap_script(self, script: JSRef<HTMLScriptElement>) {
+        let target = JS::from_rooted(script);
+        self.deref().asap_scripts.borrow_mut().retain(|s| *s != target);
+    }
+
+    fn mark_deferred_script_ready(self, _script: JSRef<HTMLScriptElement>) {
+        // The element itself already recorded its own readiness (e.g. via
+        // `ready_to_be_parser_executed`); all that's left here is to keep
+        // draining the front of the queue if parsing had already finished
+        // and was waiting on it.
+        if self.deref().parsing_finished.get() {
+            self.drain_ready_deferred_scripts();
+        }
+    }
+
+    fn module_map(self) -> Arc<Mutex<HashMap<Url, ModuleState>>> {
+        self.deref().module_map.clone()
+    }
+
+    fn finish_parsing(self) {
+        self.deref().parsing_finished.set(true);
+        self.drain_ready_deferred_scripts();
+    }
+}
+
+trait PrivateDocumentHelpers<'a> {
+    fn drain_ready_deferred_scripts(self);
+}
+
+impl<'a> PrivateDocumentHelpers<'a> for JSRef<'a, Document> {
+    fn drain_ready_deferred_scripts(self) {
+        loop {
+            let next = self.deref().deferred_scripts.borrow().first().map(|s| s.root());
+            match next {
+                Some(next) => {
+                    if !next.deref().is_ready_to_be_parser_executed() {
+                        break;
+                    }
+                    self.deref().deferred_scripts.borrow_mut().remove(0);
+                    next.deref().execute_deferred();
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Reflectable for Document {
+    fn reflector<'a>(&'a self) -> &'a Reflector {
+        self.node.reflector()
+    }
+}
@@ -3,14 +3,17 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use dom::bindings::codegen::Bindings::HTMLAudioElementBinding;
-use dom::bindings::codegen::InheritTypes::HTMLAudioElementDerived;
-use dom::bindings::js::{JSRef, Temporary};
+use dom::bindings::codegen::InheritTypes::{ElementCast, HTMLAudioElementDerived};
+use dom::bindings::error::Fallible;
+use dom::bindings::global::GlobalRef;
+use dom::bindings::js::{JSRef, Temporary, OptionalRootable};
 use dom::bindings::utils::{Reflectable, Reflector};
 use dom::document::Document;
-use dom::element::HTMLAudioElementTypeId;
+use dom::element::{AttributeHandlers, Element, HTMLAudioElementTypeId};
 use dom::eventtarget::{EventTarget, NodeTargetTypeId};
 use dom::htmlmediaelement::HTMLMediaElement;
 use dom::node::{Node, ElementNodeTypeId};
+use dom::window::WindowHelpers;
 use servo_util::str::DOMString;
 
 #[dom_struct]
@@ -36,6 +39,30 @@ impl HTMLAudioElement {
         let element = HTMLAudioElement::new_inherited(localName, prefix, document);
         Node::reflect_node(box element, document, HTMLAudioElementBinding::Wrap)
     }
+
+    /// <https://html.spec.whatwg.org/multipage/embedded-content.html#dom-audio>
+    pub fn Audio(global: GlobalRef, src: Option<DOMString>) -> Fallible<Temporary<HTMLAudioElement>> {
+        let document = global.as_window().Document().root();
+        let audio = HTMLAudioElement::new("audio".to_string(), None, document.deref()).root();
+
+        // https://html.spec.whatwg.org/multipage/embedded-content.html#dom-audio
+        // step 1: "the user agent must set the preload attribute to auto".
+        let element: JSRef<Element> = ElementCast::from_ref(audio.deref());
+        element.set_string_attribute(&atom!("preload"), "auto".to_string());
+
+        // Step 2: if `src` was given, set the `src` content attribute to
+        // its value. Unlike most content attributes, setting `src` on a
+        // media element unconditionally invokes the resource selection
+        // algorithm as one of its attribute change steps
+        // (`HTMLMediaElement`'s `VirtualMethods::after_set_attr`), so step
+        // 3 ("invoke the media element's resource selection algorithm")
+        // happens as a side effect of this and needs no separate call.
+        if let Some(src) = src {
+            element.set_url_attribute(&atom!("src"), src);
+        }
+
+        Ok(Temporary::from_rooted(audio.deref()))
+    }
 }
 
 impl Reflectable for HTMLAudioElement {
@@ -0,0 +1,218 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use dom::attr::Attr;
+use dom::attr::AttrHelpers;
+use dom::bindings::codegen::Bindings::WindowBinding::WindowMethods;
+use dom::bindings::codegen::InheritTypes::{ElementCast, EventTargetCast, HTMLElementCast};
+use dom::bindings::global::GlobalRef;
+use dom::bindings::js::{JSRef, OptionalRootable};
+use dom::bindings::refcounted::Trusted;
+use dom::bindings::utils::{Reflectable, Reflector};
+use dom::document::Document;
+use dom::element::{AttributeHandlers, Element, ElementTypeId};
+use dom::event::{Event, EventBubbles, EventCancelable};
+use dom::eventtarget::EventTarget;
+use dom::htmlelement::HTMLElement;
+use dom::node::window_from_node;
+use dom::virtualmethods::VirtualMethods;
+use dom::window::WindowHelpers;
+
+use script_task::{ScriptChan, ScriptMsg, Runnable};
+use servo_net::resource_task::{Metadata, load_whole_resource};
+use servo_util::str::DOMString;
+use servo_util::task::spawn_named;
+use std::cell::Cell;
+use url::{Url, UrlParser};
+
+/// <https://html.spec.whatwg.org/multipage/media.html#dom-media-networkstate>
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum NetworkState {
+    Empty,
+    Idle,
+    Loading,
+    NoSource,
+}
+
+/// <https://html.spec.whatwg.org/multipage/media.html#dom-media-readystate>
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum ReadyState {
+    HaveNothing,
+    HaveMetadata,
+}
+
+#[dom_struct]
+pub struct HTMLMediaElement {
+    htmlelement: HTMLElement,
+    network_state: Cell<NetworkState>,
+    ready_state: Cell<ReadyState>,
+}
+
+impl HTMLMediaElement {
+    pub fn new_inherited(type_id: ElementTypeId, localName: DOMString, prefix: Option<DOMString>,
+                          document: JSRef<Document>) -> HTMLMediaElement {
+        HTMLMediaElement {
+            htmlelement: HTMLElement::new_inherited(type_id, localName, prefix, document),
+            network_state: Cell::new(NetworkState::Empty),
+            ready_state: Cell::new(ReadyState::HaveNothing),
+        }
+    }
+}
+
+pub trait HTMLMediaElementHelpers {
+    /// Select and start loading this element's current media resource
+    /// (<https://html.spec.whatwg.org/multipage/media.html#concept-media-load-algorithm>).
+    /// Called from `after_set_attr` whenever the `src` attribute is set,
+    /// whether or not the element is in a document; this is also how the
+    /// `Audio(src)` constructor starts the load.
+    fn invoke_resource_selection_algorithm(self);
+
+    /// Called by the off-thread fetch machinery once the selected
+    /// resource's bytes have arrived (or the fetch has failed).
+    fn resource_fetch_finished(self, result: Result<(Metadata, Vec<u8>), ()>);
+
+    /// Queue a task to fire a simple, non-bubbling, non-cancelable event
+    /// named `name` at this element on the next turn of the event loop.
+    fn queue_simple_event(self, name: &'static str);
+
+    /// Fire a simple event named `name` at this element. Called from the
+    /// queued task itself.
+    fn dispatch_simple_event(self, name: &'static str);
+}
+
+impl<'a> HTMLMediaElementHelpers for JSRef<'a, HTMLMediaElement> {
+    fn invoke_resource_selection_algorithm(self) {
+        // Steps 1-3: (re)set the network/ready state for a fresh load.
+        self.network_state.set(NetworkState::NoSource);
+        self.ready_state.set(ReadyState::HaveNothing);
+
+        let element: JSRef<Element> = ElementCast::from_ref(self);
+        let src = element.get_url_attribute(&atom!("src"));
+        if src.is_empty() {
+            return;
+        }
+
+        let window = window_from_node(self).root();
+        let page = window.page();
+        let base_url = page.get_url();
+        let url = match UrlParser::new().base_url(&base_url).parse(src.as_slice()) {
+            Ok(url) => url,
+            Err(_) => {
+                self.queue_simple_event("error");
+                return;
+            }
+        };
+
+        // Step: "set the networkState ... to NETWORK_LOADING" and queue a
+        // task to fire a `loadstart` event.
+        self.network_state.set(NetworkState::Loading);
+        self.queue_simple_event("loadstart");
+
+        let resource_task = page.resource_task.clone();
+        let script_chan = window.script_chan();
+        let elem = Trusted::new(window.get_cx(), self, script_chan.clone());
+
+        spawn_named("HTMLMediaElement resource fetch".to_owned(), move || {
+            let result = load_whole_resource(&resource_task, url).map_err(|_| ());
+            script_chan.send(ScriptMsg::RunnableMsg(box MediaFetchRunnable {
+                elem: elem,
+                result: result,
+            }));
+        });
+    }
+
+    fn resource_fetch_finished(self, result: Result<(Metadata, Vec<u8>), ()>) {
+        match result {
+            Ok((_metadata, _bytes)) => {
+                // Decoding the fetched bytes into playable audio/video
+                // samples is done by the platform media backend, which is
+                // out of scope here; this element only tracks that they
+                // arrived and that playback metadata is now available.
+                self.ready_state.set(ReadyState::HaveMetadata);
+                self.network_state.set(NetworkState::Idle);
+                self.queue_simple_event("loadedmetadata");
+            }
+            Err(_) => {
+                self.network_state.set(NetworkState::NoSource);
+                self.queue_simple_event("error");
+            }
+        }
+    }
+
+    fn queue_simple_event(self, name: &'static str) {
+        let window = window_from_node(self).root();
+        let script_chan = window.script_chan();
+        let elem = Trusted::new(window.get_cx(), self, script_chan.clone());
+        script_chan.send(ScriptMsg::RunnableMsg(box SimpleEventRunnable {
+            elem: elem,
+            name: name,
+        }));
+    }
+
+    fn dispatch_simple_event(self, name: &'static str) {
+        let window = window_from_node(self).root();
+        let event = Event::new(GlobalRef::Window(window.deref()),
+                                name.to_string(),
+                                EventBubbles::DoesNotBubble,
+                                EventCancelable::NotCancelable).root();
+        let target: JSRef<EventTarget> = EventTargetCast::from_ref(self);
+        event.deref().fire(target);
+    }
+}
+
+impl<'a> VirtualMethods for JSRef<'a, HTMLMediaElement> {
+    fn super_type<'a>(&'a self) -> Option<&'a VirtualMethods> {
+        let htmlelement: &JSRef<HTMLElement> = HTMLElementCast::from_borrowed_ref(self);
+        Some(htmlelement as &VirtualMethods)
+    }
+
+    fn after_set_attr(&self, attr: JSRef<Attr>) {
+        match self.super_type() {
+            Some(ref s) => s.after_set_attr(attr),
+            _ => (),
+        }
+        // https://html.spec.whatwg.org/multipage/media.html#location-of-the-media-resource
+        // "If a src attribute ... is added ... the user agent must invoke
+        // the media element's resource selection algorithm."
+        if attr.local_name() == &atom!("src") {
+            self.invoke_resource_selection_algorithm();
+        }
+    }
+}
+
+impl Reflectable for HTMLMediaElement {
+    fn reflector<'a>(&'a self) -> &'a Reflector {
+        self.htmlelement.reflector()
+    }
+}
+
+/// Delivers the result of an off-thread fetch of a media element's
+/// selected resource back to its element on the script task.
+struct MediaFetchRunnable {
+    elem: Trusted<HTMLMediaElement>,
+    result: Result<(Metadata, Vec<u8>), ()>,
+}
+
+impl Runnable for MediaFetchRunnable {
+    fn handler(self: Box<MediaFetchRunnable>) {
+        let this = *self;
+        let elem = this.elem.root();
+        elem.deref().resource_fetch_finished(this.result);
+    }
+}
+
+/// Fires a simple event at a media element on the script task, queued to
+/// run asynchronously.
+struct SimpleEventRunnable {
+    elem: Trusted<HTMLMediaElement>,
+    name: &'static str,
+}
+
+impl Runnable for SimpleEventRunnable {
+    fn handler(self: Box<SimpleEventRunnable>) {
+        let this = *self;
+        let elem = this.elem.root();
+        elem.deref().dispatch_simple_event(this.name);
+    }
+}
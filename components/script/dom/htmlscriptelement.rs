@@ -8,25 +8,37 @@ use dom::bindings::codegen::Bindings::AttrBinding::AttrMethods;
 use dom::bindings::codegen::Bindings::HTMLScriptElementBinding;
 use dom::bindings::codegen::Bindings::HTMLScriptElementBinding::HTMLScriptElementMethods;
 use dom::bindings::codegen::Bindings::NodeBinding::NodeMethods;
+use dom::bindings::codegen::Bindings::WindowBinding::WindowMethods;
 use dom::bindings::codegen::InheritTypes::{HTMLScriptElementDerived, HTMLScriptElementCast};
-use dom::bindings::codegen::InheritTypes::{ElementCast, HTMLElementCast, NodeCast};
+use dom::bindings::codegen::InheritTypes::{ElementCast, EventTargetCast, HTMLElementCast, NodeCast};
+use dom::bindings::global::GlobalRef;
 use dom::bindings::js::{JSRef, Temporary, OptionalRootable};
+use dom::bindings::refcounted::Trusted;
 use dom::bindings::utils::{Reflectable, Reflector};
-use dom::document::Document;
+use dom::document::{Document, DocumentHelpers};
 use dom::element::{HTMLScriptElementTypeId, Element, AttributeHandlers};
 use dom::element::{ElementCreator, ParserCreated};
+use dom::event::{Event, EventBubbles, EventCancelable};
 use dom::eventtarget::{EventTarget, NodeTargetTypeId};
 use dom::htmlelement::HTMLElement;
 use dom::node::{Node, NodeHelpers, ElementNodeTypeId, window_from_node, CloneChildrenFlag};
 use dom::virtualmethods::VirtualMethods;
 use dom::window::WindowHelpers;
 
-use encoding::all::UTF_8;
-use encoding::types::{Encoding, DecodeReplace};
-use servo_net::resource_task::load_whole_resource;
+use encoding::all::{UTF_8, UTF_16LE, UTF_16BE};
+use encoding::label::encoding_from_whatwg_label;
+use encoding::types::{Encoding, EncodingRef, DecodeReplace};
+use hyper::header::{Origin, AccessControlAllowOrigin, AccessControlAllowCredentials};
+use script_task::{ScriptChan, ScriptMsg, Runnable};
+use servo_net::resource_task::{LoadData, Metadata, ResourceTask, load_whole_resource};
 use servo_util::str::{DOMString, HTML_SPACE_CHARACTERS, StaticStringVec};
+use servo_util::task::spawn_named;
+use std::ascii::AsciiExt;
 use std::cell::Cell;
-use url::UrlParser;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::{Url, UrlParser};
 
 #[dom_struct]
 pub struct HTMLScriptElement {
@@ -39,14 +51,100 @@ pub struct HTMLScriptElement {
     parser_inserted: Cell<bool>,
 
     /// https://html.spec.whatwg.org/multipage/scripting.html#non-blocking
-    ///
-    /// (currently unused)
     non_blocking: Cell<bool>,
 
     /// https://html.spec.whatwg.org/multipage/scripting.html#ready-to-be-parser-executed
     ///
-    /// (currently unused)
+    /// Set once the script's source (or, for a module script, its whole
+    /// graph) is available or its fetch has failed: right after the
+    /// synchronous fetch of a parser-blocking script, and when the
+    /// asynchronous fetch of a deferred, async or module script completes.
     ready_to_be_parser_executed: Cell<bool>,
+
+    /// The result of an in-flight or completed fetch for an external script,
+    /// filled in by `fetch_external_script` once the resource task responds.
+    load: RefCell<Option<ScriptOrigin>>,
+
+    /// The fully-resolved, dependency-first module graph for a `type=module`
+    /// script, filled in by `fetch_module_graph_async` once it has been
+    /// fetched and walked to completion.
+    modules: RefCell<Option<Vec<ModuleSource>>>,
+
+    /// Whether this script was loaded from an external file (i.e. has a
+    /// `src` attribute); only external scripts fire a `load` event on
+    /// successful execution.
+    was_external: Cell<bool>,
+}
+
+/// <http://whatwg.org/html/#support-the-scripting-language> distinguishes
+/// only "classic" scripts from everything else; module scripts follow a
+/// different loading and execution path entirely
+/// (<https://html.spec.whatwg.org/multipage/webappapis.html#module-script>).
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum ScriptType {
+    Classic,
+    Module,
+}
+
+/// The resolved source text of a single module in a module graph.
+#[derive(Clone)]
+struct ModuleSource {
+    url: Url,
+    source: DOMString,
+    muted_errors: bool,
+}
+
+/// Per-module state tracked in a `Document`'s module map
+/// (<https://html.spec.whatwg.org/multipage/webappapis.html#module-map>),
+/// keyed by resolved module URL so that concurrent `<script type=module>`s
+/// that import the same dependency only fetch it once.
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum ModuleState {
+    Fetching,
+    Fetched,
+    Instantiated,
+    Evaluated,
+    Errored,
+}
+
+/// The source of a script, together with the URL it should be reported
+/// against when evaluated or when errors are reported.
+#[derive(Clone)]
+struct ScriptOrigin {
+    source: DOMString,
+    url: Url,
+    /// Set for a cross-origin, no-CORS response: per
+    /// <https://fetch.spec.whatwg.org/#concept-response-type>, an opaque
+    /// response must not leak script text or location to `window.onerror`.
+    muted_errors: bool,
+}
+
+/// The request mode driven by the `crossorigin` content attribute
+/// (<https://html.spec.whatwg.org/multipage/urls-and-fetching.html#cors-settings-attribute>).
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum CORSSettings {
+    /// No `crossorigin` attribute: a "no-cors" request, whose cross-origin
+    /// responses are always opaque regardless of what headers they send.
+    NoCORS,
+    /// `crossorigin` or `crossorigin="anonymous"`: CORS without credentials.
+    Anonymous,
+    /// `crossorigin="use-credentials"`: CORS with credentials.
+    UseCredentials,
+}
+
+/// The three ways a script can be scheduled for execution, per
+/// <https://html.spec.whatwg.org/multipage/scripting.html#prepare-a-script> steps 14-15.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum ScriptExecutionMode {
+    /// Neither `async` nor `defer`: pauses the parser until the script has
+    /// been fetched and run.
+    ParserBlocking,
+    /// `defer` (and not `async`): runs in document order once parsing has
+    /// finished.
+    Deferred,
+    /// `async` (or created by script, i.e. "non-blocking"): runs as soon as
+    /// it is fetched, regardless of document order.
+    Async,
 }
 
 impl HTMLScriptElementDerived for EventTarget {
@@ -64,6 +162,9 @@ impl HTMLScriptElement {
             parser_inserted: Cell::new(creator == ParserCreated),
             non_blocking: Cell::new(creator != ParserCreated),
             ready_to_be_parser_executed: Cell::new(false),
+            load: RefCell::new(None),
+            modules: RefCell::new(None),
+            was_external: Cell::new(false),
         }
     }
 
@@ -82,8 +183,59 @@ pub trait HTMLScriptElementHelpers {
     /// Prepare a script, steps 6 and 7.
     fn is_javascript(self) -> bool;
 
+    /// Prepare a script, steps 6 and 7: classic vs. module, or `None` if the
+    /// script's type is not supported and it should be aborted.
+    fn get_script_type(self) -> Option<ScriptType>;
+
     /// Set the "already started" flag (<https://whatwg.org/html/#already-started>)
     fn mark_already_started(self);
+
+    /// Run the script now that its source is available, whatever the origin
+    /// (inline, or a completed fetch for an external script).
+    fn execute(self, origin: ScriptOrigin);
+
+    /// Called by the asynchronous fetch machinery once a `defer`red or
+    /// `async` script's source has arrived. `cors_settings` and
+    /// `document_url` are carried through from `prepare` so the response's
+    /// opacity can be determined here, on the script task.
+    fn load_finished(self, result: Result<(Metadata, Vec<u8>), ()>,
+                     cors_settings: CORSSettings, document_url: Url);
+
+    /// Run a `defer`red script whose source has already arrived. Called by
+    /// `Document` in document order once parsing has finished.
+    fn execute_deferred(self);
+
+    /// Whether this script's source (or, for a module script, its whole
+    /// graph) has arrived, or its fetch has failed, so that `Document` may
+    /// run it or move past it. Used by
+    /// `Document` to decide how far down its deferred list it can drain.
+    fn is_ready_to_be_parser_executed(self) -> bool;
+
+    /// Kick off a non-blocking fetch of an external script, posting the
+    /// result back to this element on the script task once it arrives.
+    /// `cors_settings` is the request mode derived from the `crossorigin`
+    /// attribute; `document_url` is the element's document's URL, used to
+    /// determine whether a cross-origin response must be treated as opaque.
+    fn fetch_external_script_async(self, url: Url, cors_settings: CORSSettings, document_url: Url);
+
+    /// Fetch a module script and its whole dependency graph off-thread,
+    /// posting the resolved, dependency-first graph back to this element on
+    /// the script task once it is complete. `inline_source` is `Some` when
+    /// this is an inline `<script type=module>` (the entry module's source
+    /// is already available; only its imports need fetching).
+    fn fetch_module_graph_async(self, url: Url, inline_source: Option<DOMString>);
+
+    /// Called by the module graph fetch machinery once the graph has been
+    /// fully resolved (or has failed).
+    fn module_graph_finished(self, result: Result<Vec<ModuleSource>, ()>);
+
+    /// Queue a task to fire a simple, non-bubbling, non-cancelable event
+    /// named `name` at this element on the next turn of the event loop.
+    fn queue_simple_event(self, name: &'static str);
+
+    /// Fire a simple event named `name` at this element. Called from the
+    /// queued task itself; never call this directly from `prepare`.
+    fn dispatch_simple_event(self, name: &'static str);
 }
 
 /// Supported script types as defined by
@@ -134,9 +286,10 @@ impl<'a> HTMLScriptElementHelpers for JSRef<'a, HTMLScriptElement> {
             return;
         }
         // Step 6, 7.
-        if !self.is_javascript() {
-            return;
-        }
+        let script_type = match self.get_script_type() {
+            Some(script_type) => script_type,
+            None => return,
+        };
         // Step 8.
         if was_parser_inserted {
             self.parser_inserted.set(true);
@@ -157,53 +310,334 @@ impl<'a> HTMLScriptElementHelpers for JSRef<'a, HTMLScriptElement> {
         // TODO: If the script element has an `event` attribute and a `for` attribute, then run
         // these substeps...
 
-        // Step 13.
-        // TODO: If the script element has a `charset` attribute, then let the script block's
-        // character encoding for this script element be the result of getting an encoding from the
-        // value of the `charset` attribute.
+        // Step 13: the script block's character encoding, when one is
+        // needed below for an external classic script, is resolved by
+        // `determine_script_encoding` from the `charset` attribute (falling
+        // back to the response's own charset, then the document's).
 
-        // Step 14 and 15.
-        // TODO: Add support for the `defer` and `async` attributes.  (For now, we fetch all
-        // scripts synchronously and execute them immediately.)
         let window = window_from_node(self).root();
         let page = window.page();
         let base_url = page.get_url();
 
-        let (source, url) = match element.get_attribute(ns!(""), &atom!("src")).root() {
+        if script_type == ScriptType::Module {
+            // Module scripts are always deferred, whether or not the
+            // `defer` attribute is present.
+            let document = window.Document().root();
+            document.add_deferred_script(self);
+
+            match element.get_attribute(ns!(""), &atom!("src")).root() {
+                Some(src) => {
+                    if src.deref().Value().is_empty() {
+                        self.ready_to_be_parser_executed.set(true);
+                        document.mark_deferred_script_ready(self);
+                        self.queue_simple_event("error");
+                        return;
+                    }
+                    match UrlParser::new().base_url(&base_url).parse(src.deref().Value().as_slice()) {
+                        Ok(url) => {
+                            self.was_external.set(true);
+                            self.fetch_module_graph_async(url, None);
+                        }
+                        Err(_) => {
+                            self.ready_to_be_parser_executed.set(true);
+                            document.mark_deferred_script_ready(self);
+                            self.queue_simple_event("error");
+                            error!("error parsing URL for module script {}", src.deref().Value());
+                        }
+                    }
+                }
+                None => self.fetch_module_graph_async(base_url, Some(text)),
+            }
+            return;
+        }
+
+        // Steps 14 and 15.
+        match element.get_attribute(ns!(""), &atom!("src")).root() {
             Some(src) => {
                 if src.deref().Value().is_empty() {
-                    // TODO: queue a task to fire a simple event named `error` at the element
+                    self.queue_simple_event("error");
                     return;
                 }
-                match UrlParser::new().base_url(&base_url).parse(src.deref().Value().as_slice()) {
-                    Ok(url) => {
-                        // TODO: Do a potentially CORS-enabled fetch with the mode being the current
-                        // state of the element's `crossorigin` content attribute, the origin being
-                        // the origin of the script element's node document, and the default origin
-                        // behaviour set to taint.
-                        match load_whole_resource(&page.resource_task, url) {
+                let url = match UrlParser::new().base_url(&base_url).parse(src.deref().Value().as_slice()) {
+                    Ok(url) => url,
+                    Err(_) => {
+                        self.queue_simple_event("error");
+                        error!("error parsing URL for script {}", src.deref().Value());
+                        return;
+                    }
+                };
+                self.was_external.set(true);
+
+                // The `crossorigin` attribute decides the request mode of
+                // the fetch; the origin of the element's node document is
+                // sent as the `Origin` header for CORS and use-credentials
+                // requests. Absent `crossorigin`, the request is "no-cors"
+                // and any cross-origin response comes back opaque.
+                let cors_settings = script_cors_settings(element);
+
+                // `async` (including scripts inserted by script, which are
+                // non-blocking by default) takes precedence over `defer`.
+                let mode = if self.non_blocking.get() || element.has_attribute(&atom!("async")) {
+                    ScriptExecutionMode::Async
+                } else if was_parser_inserted && element.has_attribute(&atom!("defer")) {
+                    ScriptExecutionMode::Deferred
+                } else {
+                    ScriptExecutionMode::ParserBlocking
+                };
+
+                let document = window.Document().root();
+                match mode {
+                    ScriptExecutionMode::ParserBlocking => {
+                        // The parser is already paused on us; a synchronous
+                        // fetch keeps it paused without any extra bookkeeping.
+                        let load_data = build_load_data(url, cors_settings, &base_url);
+                        match load_whole_resource(&page.resource_task, load_data) {
                             Ok((metadata, bytes)) => {
-                                // TODO: use the charset from step 13.
-                                let source = UTF_8.decode(bytes.as_slice(), DecodeReplace).unwrap();
-                                (source, metadata.final_url)
+                                let (encoding, bytes) = determine_script_encoding(
+                                    element, document.deref(), metadata.charset.clone(), bytes.as_slice());
+                                let source = encoding.decode(bytes, DecodeReplace).unwrap();
+                                let muted_errors = is_response_opaque(cors_settings, &base_url, &metadata);
+                                self.ready_to_be_parser_executed.set(true);
+                                self.execute(ScriptOrigin {
+                                    source: source,
+                                    url: metadata.final_url,
+                                    muted_errors: muted_errors,
+                                });
+                                self.queue_simple_event("load");
                             }
                             Err(_) => {
+                                self.queue_simple_event("error");
                                 error!("error loading script {}", src.deref().Value());
-                                return;
                             }
                         }
                     }
-                    Err(_) => {
-                        // TODO: queue a task to fire a simple event named `error` at the element
-                        error!("error parsing URL for script {}", src.deref().Value());
-                        return;
+                    ScriptExecutionMode::Deferred => {
+                        document.add_deferred_script(self);
+                        self.fetch_external_script_async(url, cors_settings, base_url.clone());
+                    }
+                    ScriptExecutionMode::Async => {
+                        document.add_asap_script(self);
+                        self.fetch_external_script_async(url, cors_settings, base_url.clone());
                     }
                 }
             }
-            None => (text, base_url)
+            None => {
+                self.execute(ScriptOrigin { source: text, url: base_url, muted_errors: false });
+            }
+        }
+    }
+
+    fn execute(self, origin: ScriptOrigin) {
+        let window = window_from_node(self).root();
+        // An opaque, cross-origin no-cors response must not leak its script
+        // text or location to `window.onerror`; reporting against an empty
+        // URL keeps the muted script's errors from identifying it.
+        let report_url = if origin.muted_errors { "".to_string() } else { origin.url.serialize() };
+        window.evaluate_script_with_result(origin.source.as_slice(),
+                                            report_url.as_slice());
+    }
+
+    fn load_finished(self, result: Result<(Metadata, Vec<u8>), ()>,
+                     cors_settings: CORSSettings, document_url: Url) {
+        let window = window_from_node(self).root();
+        let document = window.Document().root();
+        let element: JSRef<Element> = ElementCast::from_ref(self);
+        // The same test `prepare` used to choose `ScriptExecutionMode::Async`.
+        let is_async = self.non_blocking.get() || element.has_attribute(&atom!("async"));
+
+        match result {
+            Ok((metadata, bytes)) => {
+                let (encoding, bytes) = determine_script_encoding(
+                    element, document.deref(), metadata.charset.clone(), bytes.as_slice());
+                let source = encoding.decode(bytes, DecodeReplace).unwrap();
+                let muted_errors = is_response_opaque(cors_settings, &document_url, &metadata);
+                let origin = ScriptOrigin { source: source, url: metadata.final_url, muted_errors: muted_errors };
+
+                self.ready_to_be_parser_executed.set(true);
+                *self.load.borrow_mut() = Some(origin.clone());
+                if is_async {
+                    // `async`: run the moment the fetch completes, whatever
+                    // order it arrives in relative to other scripts.
+                    document.remove_asap_script(self);
+                    self.execute(origin);
+                    self.queue_simple_event("load");
+                } else {
+                    // `defer`: stay queued; `Document` runs the deferred
+                    // list in order once parsing has finished.
+                    document.mark_deferred_script_ready(self);
+                }
+            }
+            Err(_) => {
+                // A failed fetch is still "ready": it leaves nothing queued
+                // to execute, but the deferred list (and, for `async`
+                // scripts, the asap set) must be able to move past this
+                // entry rather than stall on it forever.
+                self.ready_to_be_parser_executed.set(true);
+                if is_async {
+                    document.remove_asap_script(self);
+                } else {
+                    document.mark_deferred_script_ready(self);
+                }
+                self.queue_simple_event("error");
+                error!("error loading script asynchronously");
+            }
+        }
+    }
+
+    fn execute_deferred(self) {
+        if let Some(modules) = self.modules.borrow_mut().take() {
+            let window = window_from_node(self).root();
+            let document = window.Document().root();
+            let module_map = document.module_map();
+
+            // Depth-first post-order: `modules` was built so that every
+            // dependency already precedes the modules that import it.
+            for module in modules.into_iter() {
+                let url = module.url.clone();
+                let muted_errors = module.muted_errors;
+                self.execute(ScriptOrigin { source: module.source, url: module.url, muted_errors: muted_errors });
+                module_map.lock().unwrap().insert(url, ModuleState::Evaluated);
+            }
+            if self.was_external.get() {
+                self.queue_simple_event("load");
+            }
+            return;
+        }
+        match self.load.borrow_mut().take() {
+            Some(origin) => {
+                self.execute(origin);
+                self.queue_simple_event("load");
+            }
+            None => {
+                // Nothing to run: either the fetch failed (already reported
+                // via a queued `error` event in `load_finished`/
+                // `module_graph_finished`, which is also what marked this
+                // script ready so the list could reach it) or `Document`
+                // drained it before it was actually ready, which shouldn't
+                // happen since it only drains entries that report ready.
+            }
+        }
+    }
+
+    fn is_ready_to_be_parser_executed(self) -> bool {
+        self.ready_to_be_parser_executed.get()
+    }
+
+    fn get_script_type(self) -> Option<ScriptType> {
+        let element: JSRef<Element> = ElementCast::from_ref(self);
+        let is_module = match element.get_attribute(ns!(""), &atom!("type")).root().map(|s| s.Value()) {
+            Some(ref s) => s.as_slice().trim_chars(HTML_SPACE_CHARACTERS).eq_ignore_ascii_case("module"),
+            None => false,
         };
+        if is_module {
+            Some(ScriptType::Module)
+        } else if self.is_javascript() {
+            Some(ScriptType::Classic)
+        } else {
+            None
+        }
+    }
+
+    fn fetch_module_graph_async(self, url: Url, inline_source: Option<DOMString>) {
+        let window = window_from_node(self).root();
+        let resource_task = window.page().resource_task.clone();
+        let document_url = window.page().get_url();
+        let script_chan = window.script_chan();
+        let document = window.Document().root();
+        let module_map = document.module_map();
+        let elem = Trusted::new(window.get_cx(), self, script_chan.clone());
+
+        spawn_named("ScriptElement module fetch".to_owned(), move || {
+            let mut order = Vec::new();
+            // https://html.spec.whatwg.org/multipage/webappapis.html#fetch-a-module-script-tree
+            // Module scripts default to CORS "anonymous", unlike classic
+            // scripts' default of "no-cors".
+            let result = fetch_module_and_deps(&resource_task, url, inline_source,
+                                                CORSSettings::Anonymous, &document_url,
+                                                &module_map, &mut order)
+                             .map(|_| order);
+            script_chan.send(ScriptMsg::RunnableMsg(box ModuleGraphRunnable {
+                elem: elem,
+                result: result,
+            }));
+        });
+    }
 
-        window.evaluate_script_with_result(source.as_slice(), url.serialize().as_slice());
+    fn module_graph_finished(self, result: Result<Vec<ModuleSource>, ()>) {
+        let window = window_from_node(self).root();
+        let document = window.Document().root();
+
+        match result {
+            Ok(modules) => {
+                // https://html.spec.whatwg.org/multipage/webappapis.html#creating-a-module-script
+                // Once every module in the graph has been fetched it is
+                // instantiated (linked) as a whole, before any of it runs.
+                {
+                    let module_map = document.module_map();
+                    let mut map = module_map.lock().unwrap();
+                    for module in modules.iter() {
+                        map.insert(module.url.clone(), ModuleState::Instantiated);
+                    }
+                }
+                *self.modules.borrow_mut() = Some(modules);
+                self.ready_to_be_parser_executed.set(true);
+                document.mark_deferred_script_ready(self);
+            }
+            Err(_) => {
+                // As with a failed classic-script fetch: still flip ready so
+                // the deferred list (module scripts are always deferred)
+                // keeps draining past this entry instead of stalling on it
+                // forever; `self.modules` stays `None` so `execute_deferred`
+                // no-ops for it.
+                self.ready_to_be_parser_executed.set(true);
+                document.mark_deferred_script_ready(self);
+                self.queue_simple_event("error");
+                error!("error building module graph for script");
+            }
+        }
+    }
+
+    fn queue_simple_event(self, name: &'static str) {
+        let window = window_from_node(self).root();
+        let script_chan = window.script_chan();
+        let elem = Trusted::new(window.get_cx(), self, script_chan.clone());
+        script_chan.send(ScriptMsg::RunnableMsg(box SimpleEventRunnable {
+            elem: elem,
+            name: name,
+        }));
+    }
+
+    fn dispatch_simple_event(self, name: &'static str) {
+        let window = window_from_node(self).root();
+        let event = Event::new(GlobalRef::Window(window.deref()),
+                                name.to_string(),
+                                EventBubbles::DoesNotBubble,
+                                EventCancelable::NotCancelable).root();
+        let target: JSRef<EventTarget> = EventTargetCast::from_ref(self);
+        event.deref().fire(target);
+    }
+
+    fn fetch_external_script_async(self, url: Url, cors_settings: CORSSettings, document_url: Url) {
+        let window = window_from_node(self).root();
+        let resource_task = window.page().resource_task.clone();
+        let script_chan = window.script_chan();
+        let elem = Trusted::new(window.get_cx(), self, script_chan.clone());
+
+        let load_data = build_load_data(url, cors_settings, &document_url);
+        spawn_named("ScriptElement fetch".to_owned(), move || {
+            // Encoding determination (step 13) needs the `charset` attribute
+            // and the document's encoding, both DOM state that isn't safe to
+            // touch off the script task; it happens back in `load_finished`,
+            // along with the opacity check, which only needs the captured
+            // `cors_settings`/`document_url`.
+            let result = load_whole_resource(&resource_task, load_data).map_err(|_| ());
+            script_chan.send(ScriptMsg::RunnableMsg(box ScriptFetchRunnable {
+                elem: elem,
+                result: result,
+                cors_settings: cors_settings,
+                document_url: document_url,
+            }));
+        });
     }
 
     fn is_javascript(self) -> bool {
@@ -323,3 +757,299 @@ impl Reflectable for HTMLScriptElement {
         self.htmlelement.reflector()
     }
 }
+
+/// Delivers the result of an off-thread fetch of an external, `defer`red or
+/// `async` script back to its element on the script task.
+struct ScriptFetchRunnable {
+    elem: Trusted<HTMLScriptElement>,
+    result: Result<(Metadata, Vec<u8>), ()>,
+    cors_settings: CORSSettings,
+    document_url: Url,
+}
+
+impl Runnable for ScriptFetchRunnable {
+    fn handler(self: Box<ScriptFetchRunnable>) {
+        let this = *self;
+        let elem = this.elem.root();
+        elem.deref().load_finished(this.result, this.cors_settings, this.document_url);
+    }
+}
+
+/// Delivers a fully-resolved module graph (or a fetch/resolution failure)
+/// back to its entry-point element on the script task.
+struct ModuleGraphRunnable {
+    elem: Trusted<HTMLScriptElement>,
+    result: Result<Vec<ModuleSource>, ()>,
+}
+
+impl Runnable for ModuleGraphRunnable {
+    fn handler(self: Box<ModuleGraphRunnable>) {
+        let this = *self;
+        let elem = this.elem.root();
+        elem.deref().module_graph_finished(this.result);
+    }
+}
+
+/// Fires a simple event at an element on the script task, queued to run
+/// asynchronously rather than inline inside `prepare`.
+struct SimpleEventRunnable {
+    elem: Trusted<HTMLScriptElement>,
+    name: &'static str,
+}
+
+impl Runnable for SimpleEventRunnable {
+    fn handler(self: Box<SimpleEventRunnable>) {
+        let this = *self;
+        let elem = this.elem.root();
+        elem.deref().dispatch_simple_event(this.name);
+    }
+}
+
+/// Determine the character encoding of an external classic script
+/// (<https://html.spec.whatwg.org/multipage/scripting.html#script-character-encoding>),
+/// step 13 of "prepare a script": a leading BOM wins outright (and is
+/// stripped from the returned slice); otherwise the element's `charset`
+/// attribute, then the response's HTTP `Content-Type` charset, then the
+/// document's own encoding, in that order.
+fn determine_script_encoding<'a>(element: JSRef<Element>,
+                                  document: JSRef<Document>,
+                                  metadata_charset: Option<String>,
+                                  bytes: &'a [u8])
+                                  -> (EncodingRef, &'a [u8]) {
+    if bytes.starts_with(&[0xEFu8, 0xBBu8, 0xBFu8]) {
+        return (UTF_8 as EncodingRef, &bytes[3..]);
+    }
+    if bytes.starts_with(&[0xFFu8, 0xFEu8]) {
+        return (UTF_16LE as EncodingRef, &bytes[2..]);
+    }
+    if bytes.starts_with(&[0xFEu8, 0xFFu8]) {
+        return (UTF_16BE as EncodingRef, &bytes[2..]);
+    }
+
+    let charset_attr = element.get_attribute(ns!(""), &atom!("charset")).root().map(|a| a.deref().Value());
+    let encoding = charset_attr.as_ref()
+                                .and_then(|s| encoding_from_whatwg_label(s.as_slice()))
+                                .or_else(|| metadata_charset.as_ref()
+                                                             .and_then(|s| encoding_from_whatwg_label(s.as_slice())))
+                                .unwrap_or_else(|| document.encoding());
+
+    (encoding, bytes)
+}
+
+/// Determine the request mode for a script fetch from the element's
+/// `crossorigin` content attribute
+/// (<https://html.spec.whatwg.org/multipage/urls-and-fetching.html#cors-settings-attribute>):
+/// absent means "no-cors"; present and equal to `use-credentials` means CORS
+/// with credentials; present with any other value (including empty) means
+/// CORS without credentials.
+fn script_cors_settings(element: JSRef<Element>) -> CORSSettings {
+    match element.get_attribute(ns!(""), &atom!("crossorigin")).root().map(|a| a.deref().Value()) {
+        None => CORSSettings::NoCORS,
+        Some(ref s) if s.as_slice().eq_ignore_ascii_case("use-credentials") => CORSSettings::UseCredentials,
+        Some(_) => CORSSettings::Anonymous,
+    }
+}
+
+/// Whether a script response must be treated as opaque
+/// (<https://fetch.spec.whatwg.org/#concept-response-type>): a same-origin
+/// response is never opaque; a cross-origin one is opaque unless it was
+/// fetched in a CORS mode and actually passes the CORS check below. A
+/// `crossorigin` attribute alone grants nothing -- only a response that
+/// names this document's origin in `Access-Control-Allow-Origin` does.
+fn is_response_opaque(cors_settings: CORSSettings, document_url: &Url, metadata: &Metadata) -> bool {
+    if same_origin(document_url, &metadata.final_url) {
+        return false;
+    }
+    match cors_settings {
+        CORSSettings::NoCORS => true,
+        CORSSettings::Anonymous | CORSSettings::UseCredentials => {
+            !cors_check_passes(cors_settings, document_url, metadata)
+        }
+    }
+}
+
+/// The CORS check (<https://fetch.spec.whatwg.org/#cors-check>): the
+/// response must carry an `Access-Control-Allow-Origin` naming this
+/// document's origin, or `*` (only when the request was `Anonymous` --
+/// `UseCredentials` responses may never be shared via the wildcard and
+/// must also carry a literal `Access-Control-Allow-Credentials: true`).
+fn cors_check_passes(cors_settings: CORSSettings, document_url: &Url, metadata: &Metadata) -> bool {
+    let headers = match metadata.headers {
+        Some(ref headers) => headers,
+        None => return false,
+    };
+    let origin_granted = match headers.get::<AccessControlAllowOrigin>() {
+        None => false,
+        Some(&AccessControlAllowOrigin::Any) => cors_settings == CORSSettings::Anonymous,
+        Some(&AccessControlAllowOrigin::Null) => false,
+        Some(&AccessControlAllowOrigin::Value(ref origin)) => {
+            UrlParser::new().parse(origin.as_slice())
+                             .map(|origin_url| same_origin(document_url, &origin_url))
+                             .unwrap_or(false)
+        }
+    };
+    if !origin_granted {
+        return false;
+    }
+    if cors_settings == CORSSettings::UseCredentials {
+        return headers.get::<AccessControlAllowCredentials>().is_some();
+    }
+    true
+}
+
+/// A simplified same-origin check (scheme, host, and port) sufficient to
+/// tell whether a script response is same-origin with its document.
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme == b.scheme && a.host() == b.host() && a.port() == b.port()
+}
+
+/// Build the resource-task request for a script fetch. A request whose
+/// mode is not "no-cors" must carry an `Origin` header naming the
+/// requesting document's origin
+/// (<https://fetch.spec.whatwg.org/#http-network-or-cache-fetch>, the
+/// "Origin header" step), which the server inspects to decide whether to
+/// grant access via `Access-Control-Allow-Origin`.
+fn build_load_data(url: Url, cors_settings: CORSSettings, document_url: &Url) -> LoadData {
+    let mut load_data = LoadData::new(url);
+    if cors_settings != CORSSettings::NoCORS {
+        if let Some(host) = document_url.host() {
+            load_data.headers.set(Origin::new(document_url.scheme.clone(), host.serialize(), document_url.port()));
+        }
+    }
+    load_data
+}
+
+/// Recursively fetch `url` and every module it statically imports, in
+/// depth-first order, appending each to `order` only once its own
+/// dependencies have already been appended (so `order` is ready to be
+/// instantiated and evaluated front-to-back). `module_map` is the owning
+/// `Document`'s module map, shared so that two module scripts that import
+/// the same URL fetch it only once and so that import cycles terminate.
+/// Every fetch in the graph -- the entry module and every transitively
+/// discovered import -- goes through the same `cors_settings`/CORS-check
+/// machinery as classic scripts (chunk0-5), gated against the root
+/// `document_url` rather than each importing module's own URL.
+fn fetch_module_and_deps(resource_task: &ResourceTask,
+                          url: Url,
+                          inline_source: Option<DOMString>,
+                          cors_settings: CORSSettings,
+                          document_url: &Url,
+                          module_map: &Arc<Mutex<HashMap<Url, ModuleState>>>,
+                          order: &mut Vec<ModuleSource>)
+                          -> Result<(), ()> {
+    {
+        let mut map = module_map.lock().unwrap();
+        match map.get(&url) {
+            Some(&ModuleState::Errored) => return Err(()),
+            // Already fetched, or still being fetched: either by an ancestor
+            // in this very graph walk, which appends it to `order` itself,
+            // or by another module script's walk, whose graph carries it.
+            Some(_) => return Ok(()),
+            None => { map.insert(url.clone(), ModuleState::Fetching); }
+        }
+    }
+
+    let (source, muted_errors) = match inline_source {
+        Some(source) => (source, false),
+        None => {
+            // Module scripts, unlike classic scripts, are always decoded as
+            // UTF-8: there is no `charset` fallback chain to honor here.
+            let load_data = build_load_data(url.clone(), cors_settings, document_url);
+            match load_whole_resource(resource_task, load_data) {
+                Ok((metadata, bytes)) => {
+                    let source = UTF_8.decode(bytes.as_slice(), DecodeReplace).unwrap();
+                    (source, is_response_opaque(cors_settings, document_url, &metadata))
+                }
+                Err(_) => {
+                    module_map.lock().unwrap().insert(url.clone(), ModuleState::Errored);
+                    return Err(());
+                }
+            }
+        }
+    };
+
+    for specifier in scan_module_specifiers(source.as_slice()).into_iter() {
+        let dep_url = match UrlParser::new().base_url(&url).parse(specifier.as_slice()) {
+            Ok(dep_url) => dep_url,
+            Err(_) => {
+                module_map.lock().unwrap().insert(url.clone(), ModuleState::Errored);
+                return Err(());
+            }
+        };
+        if fetch_module_and_deps(resource_task, dep_url, None, cors_settings, document_url,
+                                  module_map, order).is_err() {
+            module_map.lock().unwrap().insert(url.clone(), ModuleState::Errored);
+            return Err(());
+        }
+    }
+
+    module_map.lock().unwrap().insert(url.clone(), ModuleState::Fetched);
+    order.push(ModuleSource { url: url, source: source, muted_errors: muted_errors });
+    Ok(())
+}
+
+/// A lightweight static scan for `import`/`export ... from` specifiers,
+/// per step (b) of the module-graph loading algorithm. This only needs to
+/// discover dependency edges, not validate module syntax; real parsing and
+/// instantiation happens in the JS engine once the graph is fetched.
+///
+/// Scans statements (up to the next `;`, or end of source) rather than
+/// physical lines, so an import/export clause that wraps across lines --
+/// e.g. `import {\n  a,\n  b\n} from "mod";` -- is still matched as one
+/// unit instead of being split across two lines that individually look
+/// like neither a keyword nor a `from` clause.
+fn scan_module_specifiers(source: &str) -> Vec<DOMString> {
+    let mut specifiers = Vec::new();
+    let mut prev_char: Option<char> = None;
+
+    for (i, c) in source.char_indices() {
+        // Only consider `import`/`export` at a word boundary, so this
+        // doesn't fire inside identifiers like `reimport` or `exportable`.
+        let at_boundary = match prev_char {
+            Some(p) => !(p.is_alphanumeric() || p == '_' || p == '$'),
+            None => true,
+        };
+        prev_char = Some(c);
+        if !at_boundary {
+            continue;
+        }
+
+        let rest = &source[i..];
+        let is_import = rest.starts_with("import");
+        // "import" and "export" are both 6 bytes, so one keyword_len covers
+        // either case.
+        let keyword_len = "import".len();
+        if !is_import && !rest.starts_with("export") {
+            continue;
+        }
+        match rest[keyword_len..].chars().next() {
+            Some(nc) if nc.is_alphanumeric() || nc == '_' || nc == '$' => continue,
+            _ => (),
+        }
+
+        let after_keyword = &rest[keyword_len..];
+        let stmt_end = after_keyword.find(';').unwrap_or(after_keyword.len());
+        let stmt = &after_keyword[..stmt_end];
+
+        let search_from = match stmt.find("from") {
+            Some(from_idx) => Some(&stmt[from_idx + "from".len()..]),
+            None if is_import => Some(stmt),
+            None => None,
+        };
+        let search_from = match search_from {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let quote = match search_from.chars().find(|&c| c == '"' || c == '\'') {
+            Some(c) => c,
+            None => continue,
+        };
+        let quote_idx = search_from.find(quote).unwrap();
+        let after_quote = &search_from[quote_idx + quote.len_utf8()..];
+        if let Some(end) = after_quote.find(quote) {
+            specifiers.push(after_quote[..end].to_string());
+        }
+    }
+    specifiers
+}